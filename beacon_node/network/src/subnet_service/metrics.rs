@@ -0,0 +1,49 @@
+//! Prometheus metrics for the subnet service: how many subnets are actively subscribed to, how
+//! many validator duties are sharing each one, and how much subscribe/unsubscribe churn the
+//! duty-subnet state machine is producing.
+
+use metrics::*;
+use std::sync::LazyLock;
+
+pub static ACTIVE_ATTESTATION_SUBNETS: LazyLock<Result<IntGauge>> = LazyLock::new(|| {
+    try_create_int_gauge(
+        "subnet_service_active_attestation_subnets",
+        "Number of attestation subnets currently subscribed to for a validator duty",
+    )
+});
+
+pub static ACTIVE_SYNC_COMMITTEE_SUBNETS: LazyLock<Result<IntGauge>> = LazyLock::new(|| {
+    try_create_int_gauge(
+        "subnet_service_active_sync_committee_subnets",
+        "Number of sync committee subnets currently subscribed to for a validator duty",
+    )
+});
+
+/// Distribution of how many validator duties are currently sharing a single subnet subscription.
+/// A per-subnet labeled gauge would give the same information at unbounded cardinality as subnets
+/// come and go, so this is recorded as a histogram instead.
+pub static SUBSCRIBERS_PER_SUBNET: LazyLock<Result<Histogram>> = LazyLock::new(|| {
+    try_create_histogram(
+        "subnet_service_subscribers_per_subnet",
+        "Number of validator duties currently requiring a subnet, sampled whenever a duty is added",
+    )
+});
+
+/// Total `Subscribe` events emitted for validator-duty subnets. Backbone subscriptions and replays
+/// from `resubscribe` are not counted. Churn per epoch can be read off as the rate of this counter
+/// over an epoch-long window.
+pub static SUBNET_SUBSCRIBE_TOTAL: LazyLock<Result<IntCounter>> = LazyLock::new(|| {
+    try_create_int_counter(
+        "subnet_service_subnet_subscribe_total",
+        "Total number of Subscribe events emitted for validator duty subnets",
+    )
+});
+
+/// Total `Unsubscribe` events emitted for validator-duty subnets. Backbone unsubscriptions are not
+/// counted. Churn per epoch can be read off as the rate of this counter over an epoch-long window.
+pub static SUBNET_UNSUBSCRIBE_TOTAL: LazyLock<Result<IntCounter>> = LazyLock::new(|| {
+    try_create_int_counter(
+        "subnet_service_subnet_unsubscribe_total",
+        "Total number of Unsubscribe events emitted for validator duty subnets",
+    )
+});
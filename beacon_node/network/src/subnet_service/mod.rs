@@ -0,0 +1,757 @@
+//! The `SubnetService` tracks the set of gossipsub subnets (attestation and sync committee) that
+//! this node needs to be subscribed to at any given time.
+//!
+//! It owns two kinds of subscriptions:
+//!
+//! - "permanent" subnets: a deterministic, per-node set of backbone subnets (`subnets_per_node`
+//!   of them) that are advertised in the node's ENR and kept subscribed for as long as the node
+//!   runs, so the gossipsub mesh for every subnet stays healthy.
+//! - short-lived subnets: subnets a locally attached validator needs for a specific duty,
+//!   subscribed shortly before the duty and dropped once it has passed.
+//!
+//! The service is driven by the beacon chain's slot clock and is consumed as a `Stream` of
+//! `SubnetServiceMessage`s by the network service, which applies them to gossipsub/discovery/ENR.
+
+use beacon_chain::{BeaconChain, BeaconChainTypes};
+use futures::prelude::*;
+use lighthouse_network::{discv5::enr::NodeId, NetworkConfig};
+use slog::{debug, o, Logger};
+use slot_clock::SlotClock;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use types::{
+    CommitteeIndex, EthSpec, Slot, SubnetId, SyncCommitteeSubscription, SyncSubnetId,
+    ValidatorSubscription,
+};
+
+mod metrics;
+
+#[cfg(test)]
+mod tests;
+
+/// How many slots ahead of a duty its subnet is joined. Peer discovery is only requested for a
+/// duty further away than this, so a discovery query has time to complete before the join.
+pub const MIN_PEER_DISCOVERY_SLOT_LOOK_AHEAD: u64 = 2;
+
+/// The minimum number of connected peers we want on every subnet we're subscribed to. Below
+/// this, the health check re-triggers discovery for that subnet.
+const DEFAULT_MIN_SUBNET_PEERS: usize = 3;
+
+/// The initial, and minimum, backoff between re-discovery attempts for a single starved subnet.
+const MIN_DISCOVERY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The maximum backoff between re-discovery attempts for a single starved subnet.
+const MAX_DISCOVERY_BACKOFF: Duration = Duration::from_secs(60 * 30);
+
+/// The number of events an event broadcast subscriber can lag behind by before it starts missing
+/// them and receives `RecvError::Lagged` on its next `recv()`.
+const EVENT_BROADCAST_CAPACITY: usize = 1024;
+
+/// A gossipsub subnet this node can be subscribed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subnet {
+    Attestation(SubnetId),
+    SyncCommittee(SyncSubnetId),
+}
+
+/// A request from a locally attached validator to join a subnet for some duty.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subscription {
+    Attestation(ValidatorSubscription),
+    SyncCommittee(SyncCommitteeSubscription),
+}
+
+/// The lifecycle state of a short-lived (non-permanent) subnet subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubnetState {
+    /// A discovery query has been requested to find peers ahead of the duty that needs the
+    /// subnet, but the subnet hasn't been joined yet.
+    DiscoveringPeers,
+    /// Subscribed to the subnet's gossipsub mesh.
+    Subscribed,
+    /// `unsubscribe_slot` has passed; the subnet is being dropped.
+    Unsubscribing,
+}
+
+/// A request that a discovery query be run to find peers on the given subnets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubnetDiscovery {
+    pub subnet: Subnet,
+    pub min_ttl: Option<Instant>,
+}
+
+/// Events emitted by the `SubnetService`, to be applied by the network service to gossipsub,
+/// discovery and the local ENR.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubnetServiceMessage {
+    /// Join the gossipsub mesh for `Subnet`.
+    Subscribe(Subnet),
+    /// Leave the gossipsub mesh for `Subnet`.
+    Unsubscribe(Subnet),
+    /// Advertise `Subnet` in the local ENR's bitfield.
+    EnrAdd(Subnet),
+    /// Remove `Subnet` from the local ENR's bitfield.
+    EnrRemove(Subnet),
+    /// Run a discovery query to find peers on the given subnets.
+    DiscoverPeers(Vec<SubnetDiscovery>),
+}
+
+/// Identifies a single validator duty contributing to a subnet subscription, so the subnet's
+/// reference count can be decremented for exactly that duty once it no longer needs the subnet.
+/// Sync committee subscriptions carry a real `validator_index`; attestation subscriptions don't,
+/// so the committee/slot pair they were computed from stands in as their identity instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SubscriberKey {
+    Validator(u64),
+    AttestationDuty(CommitteeIndex, Slot),
+}
+
+/// A single validator duty's requirement for a subnet, tracked within a `SubnetEntry`.
+#[derive(Debug, Clone, Copy)]
+struct SubscriberState {
+    /// The slot at which this subscriber's need for the subnet ends.
+    unsubscribe_slot: Slot,
+    /// Whether this subscriber requires the subnet to be advertised in the ENR.
+    advertise_in_enr: bool,
+}
+
+/// Per-subnet state for a short-lived, validator-duty-driven subscription.
+#[derive(Debug, Clone)]
+struct SubnetEntry {
+    /// The slot from which the subnet needs to be joined. Coalesced to the earliest requesting
+    /// duty's slot.
+    subscribe_slot: Slot,
+    /// Every validator duty currently requiring this subnet, keyed by subscriber. The subnet is
+    /// only dropped once every subscriber's slot has passed, rather than on the first one to
+    /// expire.
+    subscribers: HashMap<SubscriberKey, SubscriberState>,
+    /// Whether this subnet is currently advertised in the ENR, i.e. at least one remaining
+    /// subscriber is a non-aggregator (attestation) or sync committee subscription. Recomputed
+    /// from `subscribers` whenever it shrinks, so an aggregator-only subscriber left behind after
+    /// a non-aggregator's subscription expires doesn't keep the subnet advertised.
+    advertise_in_enr: bool,
+    state: SubnetState,
+}
+
+/// Tracks the gossipsub subnets this node is, or should be, subscribed to and emits the
+/// subscribe/unsubscribe/discovery/ENR events needed to keep that true.
+pub struct SubnetService<T: BeaconChainTypes> {
+    pub(crate) beacon_chain: Arc<BeaconChain<T>>,
+    node_id: NodeId,
+    subnets_per_node: u64,
+    /// The minimum number of peers we want on a subscribed subnet before the health check stops
+    /// requesting re-discovery for it.
+    min_subnet_peers: usize,
+    /// Deterministic backbone subnets, advertised in the ENR, kept subscribed for the node's
+    /// lifetime.
+    permanent_subnets: HashSet<Subnet>,
+    /// Short-lived subnets joined for a validator duty, each tracked by its own small state
+    /// machine (`SubnetEntry`) keyed by subnet. Multiple overlapping duties on the same subnet
+    /// coalesce into a single entry rather than producing redundant subscribe/unsubscribe churn.
+    duty_subnets: HashMap<Subnet, SubnetEntry>,
+    /// The last slot the service processed; used to detect slot clock advancement.
+    current_slot: Option<Slot>,
+    /// Last-known connected peer count per subscribed subnet, fed in by the peer manager.
+    subnet_peer_counts: HashMap<Subnet, usize>,
+    /// Per-subnet re-discovery backoff state: the next time we're allowed to request discovery
+    /// again, and the backoff duration that produced it.
+    discovery_backoff: HashMap<Subnet, (Instant, Duration)>,
+    /// `false` once the node has zero attached validators: all subscriptions (including the
+    /// backbone) are torn down and no discovery is emitted until a validator attaches again.
+    gossip_enabled: bool,
+    /// Events ready to be yielded from the `Stream` implementation.
+    events: VecDeque<SubnetServiceMessage>,
+    /// Fans every emitted event out to any number of independent subscribers (see
+    /// `subscribe_events`), in addition to the primary `Stream` consumer.
+    event_broadcast: broadcast::Sender<SubnetServiceMessage>,
+    /// Sleep future that wakes the service up at (approximately) the start of the next slot.
+    next_slot_wake: Pin<Box<tokio::time::Sleep>>,
+    log: Logger,
+}
+
+impl<T: BeaconChainTypes> SubnetService<T> {
+    pub fn new(
+        beacon_chain: Arc<BeaconChain<T>>,
+        node_id: NodeId,
+        config: &NetworkConfig,
+        log: &Logger,
+    ) -> Self {
+        let log = log.new(o!("service" => "subnet_service"));
+        let subnets_per_node = beacon_chain.spec.subnets_per_node;
+        let (event_broadcast, _) = broadcast::channel(EVENT_BROADCAST_CAPACITY);
+
+        let mut service = Self {
+            beacon_chain,
+            node_id,
+            subnets_per_node,
+            min_subnet_peers: config
+                .target_subnet_peers
+                .unwrap_or(DEFAULT_MIN_SUBNET_PEERS),
+            permanent_subnets: HashSet::new(),
+            duty_subnets: HashMap::new(),
+            current_slot: None,
+            subnet_peer_counts: HashMap::new(),
+            discovery_backoff: HashMap::new(),
+            gossip_enabled: true,
+            events: VecDeque::new(),
+            event_broadcast,
+            next_slot_wake: Box::pin(tokio::time::sleep(Duration::from_secs(0))),
+            log,
+        };
+
+        service.subscribe_to_permanent_subnets();
+        service
+    }
+
+    /// Computes and subscribes to this node's deterministic set of backbone subnets, emitting
+    /// `Subscribe`/`EnrAdd` for each.
+    fn subscribe_to_permanent_subnets(&mut self) {
+        let subnet_count = self.beacon_chain.spec.attestation_subnet_count;
+        let raw = self.node_id.raw();
+        let seed = u64::from_le_bytes(raw[0..8].try_into().unwrap_or_default());
+
+        for i in 0..self.subnets_per_node {
+            let subnet = Subnet::Attestation(SubnetId::new(seed.wrapping_add(i) % subnet_count));
+            self.permanent_subnets.insert(subnet);
+            self.events.push_back(SubnetServiceMessage::Subscribe(subnet));
+            self.events.push_back(SubnetServiceMessage::EnrAdd(subnet));
+        }
+        if !self.permanent_subnets.is_empty() {
+            let discoveries = self
+                .permanent_subnets
+                .iter()
+                .map(|subnet| SubnetDiscovery {
+                    subnet: *subnet,
+                    min_ttl: None,
+                })
+                .collect();
+            self.events
+                .push_back(SubnetServiceMessage::DiscoverPeers(discoveries));
+        }
+    }
+
+    /// The backbone subnets advertised in this node's ENR.
+    pub fn permanent_subscriptions(&self) -> impl Iterator<Item = &Subnet> {
+        self.permanent_subnets.iter()
+    }
+
+    /// The short-lived, validator-duty-driven subnets currently tracked, whether or not they're
+    /// advertised in the ENR. This includes subnets still waiting on their `subscribe_slot` that
+    /// haven't been joined yet.
+    pub fn subscriptions(&self) -> impl Iterator<Item = Subnet> + '_ {
+        self.duty_subnets.keys().copied()
+    }
+
+    /// The short-lived, validator-duty-driven subnets joined for gossip stability (i.e. not
+    /// purely because a validator is an aggregator on them). Advertised in the ENR.
+    pub fn backbone_subscriptions(&self) -> impl Iterator<Item = Subnet> + '_ {
+        self.duty_subnets
+            .iter()
+            .filter(|(_, entry)| entry.advertise_in_enr)
+            .map(|(subnet, _)| *subnet)
+    }
+
+    /// The short-lived subnets joined purely because a local validator is an aggregator on
+    /// them. Never advertised in the ENR.
+    pub fn aggregation_subscriptions(&self) -> impl Iterator<Item = Subnet> + '_ {
+        self.duty_subnets
+            .iter()
+            .filter(|(_, entry)| !entry.advertise_in_enr)
+            .map(|(subnet, _)| *subnet)
+    }
+
+    /// The state of `subnet`'s short-lived subscription state machine, or `None` if it isn't
+    /// currently tracked as a duty subnet (it may still be a permanent subnet).
+    pub fn subnet_state(&self, subnet: &Subnet) -> Option<SubnetState> {
+        self.duty_subnets.get(subnet).map(|entry| entry.state)
+    }
+
+    /// Returns `true` if `subnet` is a permanent subnet or is tracked as a duty subnet. A duty
+    /// subnet still waiting on its `subscribe_slot` counts, even though it hasn't been joined yet.
+    pub fn is_subscribed(&self, subnet: &Subnet) -> bool {
+        self.permanent_subnets.contains(subnet) || self.duty_subnets.contains_key(subnet)
+    }
+
+    /// Returns a new, independent subscriber that receives every event this service emits,
+    /// mirroring exactly what's yielded from the `Stream` implementation. Each subscriber has its
+    /// own stable id and can be dropped without affecting any other subscriber or the primary
+    /// `Stream` consumer; one that falls too far behind gets `RecvError::Lagged` on its next
+    /// `recv()` rather than stalling the service.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SubnetServiceMessage> {
+        self.event_broadcast.subscribe()
+    }
+
+    /// Feeds the service a new set of connected-peer counts per subscribed subnet, fed in by the
+    /// peer manager. Subnets that drop below `min_subnet_peers` have their re-discovery timer
+    /// checked immediately.
+    pub fn update_subnet_peer_counts(&mut self, counts: HashMap<Subnet, usize>) {
+        self.subnet_peer_counts.extend(counts);
+        self.check_subnet_health();
+    }
+
+    /// Returns `true` unless the node is idle (zero attached validators), in which case every
+    /// subscription has been torn down and no discovery is emitted.
+    pub fn is_gossip_enabled(&self) -> bool {
+        self.gossip_enabled
+    }
+
+    /// Tells the service how many validators are currently attached. Transitioning to zero
+    /// tears down every subscription, including the backbone, and suppresses discovery until a
+    /// validator attaches again, at which point the backbone is fully re-established.
+    pub fn set_validators_attached(&mut self, count: usize) {
+        let was_enabled = self.gossip_enabled;
+        self.gossip_enabled = count > 0;
+
+        if was_enabled && !self.gossip_enabled {
+            self.enter_idle();
+        } else if !was_enabled && self.gossip_enabled {
+            self.subscribe_to_permanent_subnets();
+        }
+    }
+
+    /// Re-emits `Subscribe`/`EnrAdd` for every subnet this service currently considers active
+    /// (the permanent backbone and every live duty subnet, respecting each one's
+    /// `unsubscribe_slot`), without touching any internal state or emitting `Unsubscribe`/
+    /// `EnrRemove`. Call this when the network/discv5 layer signals it has restarted and lost its
+    /// gossipsub mesh and ENR state, so they're brought back in line with this service's
+    /// still-valid bookkeeping instead of spuriously tearing down and rebuilding it. A no-op while
+    /// the node is idle, since there's nothing subscribed to replay.
+    pub fn resubscribe(&mut self) {
+        if !self.gossip_enabled {
+            return;
+        }
+
+        for subnet in self.permanent_subnets.iter().copied() {
+            self.events.push_back(SubnetServiceMessage::Subscribe(subnet));
+            self.events.push_back(SubnetServiceMessage::EnrAdd(subnet));
+        }
+        for (subnet, entry) in self.duty_subnets.iter() {
+            // A subnet still waiting on its `subscribe_slot` was never actually joined, so
+            // there's nothing to replay for it yet; `promote_ready_subnets` will subscribe it in
+            // the usual way once its window arrives.
+            if entry.state != SubnetState::Subscribed {
+                continue;
+            }
+            self.events
+                .push_back(SubnetServiceMessage::Subscribe(*subnet));
+            if entry.advertise_in_enr {
+                self.events.push_back(SubnetServiceMessage::EnrAdd(*subnet));
+            }
+        }
+    }
+
+    /// Unsubscribes from every subnet (backbone and aggregation) and removes the backbone from
+    /// the ENR, saving bandwidth on a pure relay/bootstrap node with no attached validators.
+    fn enter_idle(&mut self) {
+        for subnet in std::mem::take(&mut self.permanent_subnets) {
+            self.events
+                .push_back(SubnetServiceMessage::Unsubscribe(subnet));
+            self.events
+                .push_back(SubnetServiceMessage::EnrRemove(subnet));
+        }
+        for (subnet, entry) in std::mem::take(&mut self.duty_subnets) {
+            self.events
+                .push_back(SubnetServiceMessage::Unsubscribe(subnet));
+            if entry.advertise_in_enr {
+                self.events
+                    .push_back(SubnetServiceMessage::EnrRemove(subnet));
+            }
+            metrics::inc_counter(&metrics::SUBNET_UNSUBSCRIBE_TOTAL);
+        }
+        self.discovery_backoff.clear();
+        self.update_subnet_count_metrics();
+    }
+
+    /// Requests discovery, with exponential backoff, for every subscribed subnet that doesn't
+    /// have enough connected peers. Called on every slot tick and whenever peer counts change.
+    /// A no-op while the node is idle.
+    fn check_subnet_health(&mut self) {
+        if !self.gossip_enabled {
+            return;
+        }
+        let now = Instant::now();
+        let starved_subnets: Vec<Subnet> = self
+            .permanent_subnets
+            .iter()
+            .chain(self.duty_subnets.keys())
+            .copied()
+            .filter(|subnet| {
+                // A subnet with no entry yet hasn't had its peer count reported at all (the peer
+                // manager reports counts asynchronously after discovery/connections happen), which
+                // is not the same as a confirmed zero. Only a reported count below the threshold
+                // counts as starved, so a subnet isn't flagged before it's ever had a chance.
+                self.subnet_peer_counts
+                    .get(subnet)
+                    .is_some_and(|count| *count < self.min_subnet_peers)
+            })
+            .collect();
+
+        let mut to_discover = Vec::new();
+        for subnet in starved_subnets {
+            let ready = match self.discovery_backoff.get(&subnet) {
+                Some((next_attempt, _)) => now >= *next_attempt,
+                None => true,
+            };
+            if !ready {
+                continue;
+            }
+
+            let backoff = self
+                .discovery_backoff
+                .get(&subnet)
+                .map(|(_, backoff)| (*backoff * 2).min(MAX_DISCOVERY_BACKOFF))
+                .unwrap_or(MIN_DISCOVERY_BACKOFF);
+            self.discovery_backoff
+                .insert(subnet, (now + backoff, backoff));
+            to_discover.push(SubnetDiscovery {
+                subnet,
+                min_ttl: None,
+            });
+        }
+
+        // Subnets that are healthy again get their backoff reset, so a future dip starts from
+        // `MIN_DISCOVERY_BACKOFF` rather than wherever the last starvation episode left off.
+        self.discovery_backoff.retain(|subnet, _| {
+            self.subnet_peer_counts
+                .get(subnet)
+                .is_some_and(|count| *count < self.min_subnet_peers)
+        });
+
+        if !to_discover.is_empty() {
+            debug!(self.log, "Re-triggering discovery for starved subnets"; "count" => to_discover.len());
+            self.events
+                .push_back(SubnetServiceMessage::DiscoverPeers(to_discover));
+        }
+    }
+
+    /// Registers new validator subscriptions, subscribing to any subnets that aren't already
+    /// covered and coalescing any that are by reference-counting their `unsubscribe_slot`s.
+    ///
+    /// Subscribing is slot-gated: a subnet isn't actually joined until the slot clock reaches its
+    /// `subscribe_slot` (see `promote_ready_subnets`), unless that slot has already passed by the
+    /// time this is called, in which case there's no benefit to waiting and it's joined
+    /// immediately. Discovery queries for every subnet that still has time to benefit from one
+    /// are batched into a single `DiscoverPeers` event per call, the same way
+    /// `subscribe_to_permanent_subnets` batches the backbone's.
+    pub fn validator_subscriptions(&mut self, subscriptions: impl Iterator<Item = Subscription>) {
+        let spec = &self.beacon_chain.spec;
+        let current_slot = self.beacon_chain.slot_clock.now().unwrap_or_else(Slot::new);
+        let mut discovery_subnets: HashSet<Subnet> = HashSet::new();
+
+        for subscription in subscriptions {
+            match subscription {
+                Subscription::Attestation(sub) => {
+                    let Ok(subnet_id) = SubnetId::compute_subnet::<T::EthSpec>(
+                        sub.slot,
+                        sub.attestation_committee_index,
+                        sub.committee_count_at_slot,
+                        spec,
+                    ) else {
+                        continue;
+                    };
+                    let subnet = Subnet::Attestation(subnet_id);
+                    // The subnet is joined `MIN_PEER_DISCOVERY_SLOT_LOOK_AHEAD` slots ahead of
+                    // the duty and dropped once the slot after it begins, so it stays joined for
+                    // the whole duty slot. A non-aggregator only needs the
+                    // subnet for gossip stability, so it joins the ENR-advertised backbone;
+                    // an aggregator's subscription must stay off the ENR.
+                    let subscribe_slot = Slot::new(
+                        sub.slot
+                            .as_u64()
+                            .saturating_sub(MIN_PEER_DISCOVERY_SLOT_LOOK_AHEAD),
+                    );
+                    // If the look-ahead window hasn't already elapsed, there's time for a
+                    // discovery query to complete before we need to join. Otherwise skip it and
+                    // just subscribe directly once `subscribe_slot` is reached.
+                    if subscribe_slot > current_slot {
+                        discovery_subnets.insert(subnet);
+                    }
+                    self.add_subscription(
+                        subnet,
+                        SubscriberKey::AttestationDuty(sub.attestation_committee_index, sub.slot),
+                        subscribe_slot,
+                        sub.slot + 1,
+                        !sub.is_aggregator,
+                    );
+                }
+                Subscription::SyncCommittee(sub) => {
+                    let Ok(subnet_ids) =
+                        SyncSubnetId::compute_subnets_for_sync_committee::<T::EthSpec>(
+                            &sub.sync_committee_indices,
+                        )
+                    else {
+                        continue;
+                    };
+                    let unsubscribe_slot = sub.until_epoch.start_slot(T::EthSpec::slots_per_epoch());
+                    for subnet_id in subnet_ids {
+                        let subnet = Subnet::SyncCommittee(subnet_id);
+                        // Sync committee participation is needed immediately, so it's always
+                        // subscribed to right away and discovery always runs alongside it, and
+                        // it's always advertised in the ENR.
+                        discovery_subnets.insert(subnet);
+                        self.add_subscription(
+                            subnet,
+                            SubscriberKey::Validator(sub.validator_index),
+                            current_slot,
+                            unsubscribe_slot,
+                            true,
+                        );
+                    }
+                }
+            }
+        }
+
+        self.promote_ready_subnets(current_slot);
+
+        if !discovery_subnets.is_empty() {
+            let discoveries = discovery_subnets
+                .into_iter()
+                .map(|subnet| SubnetDiscovery {
+                    subnet,
+                    min_ttl: None,
+                })
+                .collect();
+            self.events
+                .push_back(SubnetServiceMessage::DiscoverPeers(discoveries));
+        }
+    }
+
+    /// Records that `subscriber` needs `subnet` from `subscribe_slot` until at least
+    /// `unsubscribe_slot`, creating its state machine entry on first subscription and
+    /// reference-counting it into an existing one otherwise: each subscriber's own unsubscribe
+    /// slot is tracked independently, so the subnet is only dropped once every subscriber's slot
+    /// has passed, rather than generating redundant subscribe/unsubscribe churn whenever one of
+    /// several overlapping duties resolves. This only updates bookkeeping; the entry isn't
+    /// actually subscribed (no `Subscribe`/`EnrAdd` emitted) until `promote_ready_subnets` finds
+    /// its `subscribe_slot` has arrived, except `EnrAdd` is emitted immediately if the entry is
+    /// already subscribed and this is the first subscriber to require ENR advertisement.
+    fn add_subscription(
+        &mut self,
+        subnet: Subnet,
+        subscriber: SubscriberKey,
+        subscribe_slot: Slot,
+        unsubscribe_slot: Slot,
+        advertise_in_enr: bool,
+    ) {
+        if self.permanent_subnets.contains(&subnet) {
+            return;
+        }
+
+        let was_advertised = self
+            .duty_subnets
+            .get(&subnet)
+            .map(|entry| entry.advertise_in_enr)
+            .unwrap_or(false);
+        let was_subscribed = self
+            .duty_subnets
+            .get(&subnet)
+            .map(|entry| entry.state == SubnetState::Subscribed)
+            .unwrap_or(false);
+
+        let entry = self.duty_subnets.entry(subnet).or_insert_with(|| SubnetEntry {
+            subscribe_slot,
+            subscribers: HashMap::new(),
+            advertise_in_enr,
+            state: SubnetState::DiscoveringPeers,
+        });
+        // A subscriber resubmitting with an earlier end keeps its later one.
+        let unsubscribe_slot = entry
+            .subscribers
+            .get(&subscriber)
+            .map_or(unsubscribe_slot, |existing| {
+                existing.unsubscribe_slot.max(unsubscribe_slot)
+            });
+        entry.subscribers.insert(
+            subscriber,
+            SubscriberState {
+                unsubscribe_slot,
+                advertise_in_enr,
+            },
+        );
+        if subscribe_slot < entry.subscribe_slot {
+            entry.subscribe_slot = subscribe_slot;
+        }
+        entry.advertise_in_enr = entry
+            .subscribers
+            .values()
+            .any(|subscriber| subscriber.advertise_in_enr);
+        metrics::observe(
+            &metrics::SUBSCRIBERS_PER_SUBNET,
+            entry.subscribers.len() as f64,
+        );
+
+        if was_subscribed && advertise_in_enr && !was_advertised {
+            self.events.push_back(SubnetServiceMessage::EnrAdd(subnet));
+        }
+        self.update_subnet_count_metrics();
+    }
+
+    /// Joins every duty subnet still waiting on its `subscribe_slot`, emitting `Subscribe` (and
+    /// `EnrAdd`, if it's ENR-advertised) for each one whose `subscribe_slot` is now at or before
+    /// `current_slot`. Called both right after new subscriptions are registered (to catch any
+    /// that had no time left for discovery) and on every slot tick (to catch the rest as their
+    /// window arrives).
+    fn promote_ready_subnets(&mut self, current_slot: Slot) {
+        for (subnet, entry) in self.duty_subnets.iter_mut() {
+            if entry.state == SubnetState::DiscoveringPeers && entry.subscribe_slot <= current_slot
+            {
+                entry.state = SubnetState::Subscribed;
+                self.events.push_back(SubnetServiceMessage::Subscribe(*subnet));
+                metrics::inc_counter(&metrics::SUBNET_SUBSCRIBE_TOTAL);
+                if entry.advertise_in_enr {
+                    self.events.push_back(SubnetServiceMessage::EnrAdd(*subnet));
+                }
+            }
+        }
+    }
+
+    /// Drops any duty subnet whose last remaining subscriber's `unsubscribe_slot` has passed as
+    /// of `current_slot`, emitting `EnrRemove` first for those that were ENR-advertised and then
+    /// `Unsubscribe`. A subnet with at least one subscriber whose slot hasn't passed yet stays
+    /// subscribed, but has its ENR advertisement demoted (with its own `EnrRemove`) if the
+    /// subscriber(s) that required it have all expired while an aggregator-only subscriber
+    /// remains.
+    fn prune_expired_subscriptions(&mut self, current_slot: Slot) {
+        for (subnet, entry) in self.duty_subnets.iter_mut() {
+            entry
+                .subscribers
+                .retain(|_, subscriber| current_slot < subscriber.unsubscribe_slot);
+
+            if entry.subscribers.is_empty() {
+                entry.state = SubnetState::Unsubscribing;
+                continue;
+            }
+
+            let still_advertised = entry
+                .subscribers
+                .values()
+                .any(|subscriber| subscriber.advertise_in_enr);
+            if entry.advertise_in_enr && !still_advertised {
+                self.events
+                    .push_back(SubnetServiceMessage::EnrRemove(*subnet));
+            }
+            entry.advertise_in_enr = still_advertised;
+        }
+
+        let expired: Vec<(Subnet, bool)> = self
+            .duty_subnets
+            .iter()
+            .filter(|(_, entry)| entry.state == SubnetState::Unsubscribing)
+            .map(|(subnet, entry)| (*subnet, entry.advertise_in_enr))
+            .collect();
+
+        for (subnet, was_advertised) in expired {
+            self.duty_subnets.remove(&subnet);
+            if was_advertised {
+                self.events
+                    .push_back(SubnetServiceMessage::EnrRemove(subnet));
+            }
+            self.events
+                .push_back(SubnetServiceMessage::Unsubscribe(subnet));
+            metrics::inc_counter(&metrics::SUBNET_UNSUBSCRIBE_TOTAL);
+        }
+        self.update_subnet_count_metrics();
+    }
+
+    /// Refreshes the active-subnet-count gauges from the current `duty_subnets` map.
+    fn update_subnet_count_metrics(&self) {
+        let (attestation_count, sync_committee_count) = self.duty_subnets.keys().fold(
+            (0i64, 0i64),
+            |(attestation, sync_committee), subnet| match subnet {
+                Subnet::Attestation(_) => (attestation + 1, sync_committee),
+                Subnet::SyncCommittee(_) => (attestation, sync_committee + 1),
+            },
+        );
+        metrics::set_gauge(&metrics::ACTIVE_ATTESTATION_SUBNETS, attestation_count);
+        metrics::set_gauge(&metrics::ACTIVE_SYNC_COMMITTEE_SUBNETS, sync_committee_count);
+    }
+
+    /// Detects a slot-clock discontinuity (the clock having jumped forward by more than one slot
+    /// since it was last observed, e.g. after waking from sleep, a GC pause, or a resync) and, if
+    /// one occurred, re-triggers discovery for every currently subscribed subnet in a single
+    /// fold over the whole skipped range, rather than per skipped slot. This covers any duty
+    /// whose discovery window fell entirely inside the gap; `prune_expired_subscriptions`
+    /// already garbage-collects anything whose `unsubscribe_slot` elapsed during it regardless of
+    /// how large the jump was, since it compares directly against the final observed slot.
+    fn handle_slot_gap(&mut self, previous_slot: Slot, current_slot: Slot) {
+        if current_slot <= previous_slot + 1 {
+            return;
+        }
+        debug!(
+            self.log,
+            "Detected slot clock discontinuity, re-evaluating skipped subscription window";
+            "previous_slot" => previous_slot.as_u64(),
+            "current_slot" => current_slot.as_u64(),
+        );
+
+        let discoveries: Vec<SubnetDiscovery> = self
+            .permanent_subnets
+            .iter()
+            .chain(self.duty_subnets.keys())
+            .copied()
+            .map(|subnet| SubnetDiscovery {
+                subnet,
+                min_ttl: None,
+            })
+            .collect();
+        if !discoveries.is_empty() {
+            self.events
+                .push_back(SubnetServiceMessage::DiscoverPeers(discoveries));
+        }
+    }
+
+    /// Advances the service by one slot: detects clock gaps, prunes expired subscriptions and
+    /// runs the subnet health check. A no-op while the node is idle, beyond recording the slot.
+    fn advance_slot(&mut self, slot: Slot) {
+        if !self.gossip_enabled {
+            self.current_slot = Some(slot);
+            return;
+        }
+        if let Some(previous_slot) = self.current_slot {
+            self.handle_slot_gap(previous_slot, slot);
+        }
+        self.promote_ready_subnets(slot);
+        self.prune_expired_subscriptions(slot);
+        self.check_subnet_health();
+        self.current_slot = Some(slot);
+    }
+}
+
+impl<T: BeaconChainTypes> Stream for SubnetService<T> {
+    type Item = SubnetServiceMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(event) = self.events.pop_front() {
+            let _ = self.event_broadcast.send(event.clone());
+            return Poll::Ready(Some(event));
+        }
+
+        loop {
+            match self.next_slot_wake.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    let Some(duration_to_next_slot) =
+                        self.beacon_chain.slot_clock.duration_to_next_slot()
+                    else {
+                        return Poll::Pending;
+                    };
+                    let deadline = tokio::time::Instant::now() + duration_to_next_slot;
+                    self.next_slot_wake.as_mut().reset(deadline);
+
+                    if let Some(slot) = self.beacon_chain.slot_clock.now() {
+                        self.advance_slot(slot);
+                    }
+
+                    if let Some(event) = self.events.pop_front() {
+                        let _ = self.event_broadcast.send(event.clone());
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -762,4 +762,506 @@ mod test {
             .count();
         assert_eq!(sync_committee_subscriptions, 1);
     }
+
+    #[tokio::test]
+    async fn aggregator_subscription_is_not_advertised_in_enr() {
+        let committee_index = 1;
+        let mut subnet_service = get_subnet_service();
+        let _events = get_events(&mut subnet_service, None, 0).await;
+
+        let current_slot = subnet_service
+            .beacon_chain
+            .slot_clock
+            .now()
+            .expect("Could not get current slot");
+
+        // An aggregator-only subscription should join the gossip mesh but stay off the ENR.
+        let subscriptions = vec![get_subscription(committee_index, current_slot + 1, 1, true)];
+        subnet_service.validator_subscriptions(subscriptions.into_iter());
+
+        let events = get_events(&mut subnet_service, None, 1).await;
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::Subscribe(_))));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::EnrAdd(_))));
+        assert_eq!(subnet_service.aggregation_subscriptions().count(), 1);
+        assert_eq!(subnet_service.backbone_subscriptions().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn non_aggregator_subscription_joins_backbone_and_is_advertised() {
+        let committee_index = 1;
+        let mut subnet_service = get_subnet_service();
+        let _events = get_events(&mut subnet_service, None, 0).await;
+
+        let current_slot = subnet_service
+            .beacon_chain
+            .slot_clock
+            .now()
+            .expect("Could not get current slot");
+
+        // A non-aggregator subscription is only needed for gossip stability, so it joins the
+        // backbone set and is advertised in the ENR.
+        let subscriptions = vec![get_subscription(committee_index, current_slot + 1, 1, false)];
+        subnet_service.validator_subscriptions(subscriptions.into_iter());
+
+        let events = get_events(&mut subnet_service, None, 1).await;
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::Subscribe(_))));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::EnrAdd(_))));
+        assert_eq!(subnet_service.backbone_subscriptions().count(), 1);
+        assert_eq!(subnet_service.aggregation_subscriptions().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn expiring_enr_subscriber_demotes_but_keeps_subnet_subscribed() {
+        let committee_count = 1;
+        let com1 = 4;
+        let com2 = 0;
+
+        let mut subnet_service = get_subnet_service();
+        let _events = get_events(&mut subnet_service, None, 1).await;
+        let current_slot = subnet_service
+            .beacon_chain
+            .slot_clock
+            .now()
+            .expect("Could not get current slot");
+
+        // Two subscriptions to the same subnet: a non-aggregator (ENR-requiring) one that expires
+        // first, and a longer-lived aggregator-only (non-ENR) one that outlives it.
+        let non_aggregator_slot = current_slot + Slot::new(1);
+        let aggregator_slot = current_slot + Slot::new(5);
+        let non_aggregator_sub =
+            get_subscription(com1, non_aggregator_slot, committee_count, false);
+        let aggregator_sub = get_subscription(com2, aggregator_slot, committee_count, true);
+
+        let subnet_id = SubnetId::compute_subnet::<MainnetEthSpec>(
+            non_aggregator_slot,
+            com1,
+            committee_count,
+            &subnet_service.beacon_chain.spec,
+        )
+        .unwrap();
+        assert_eq!(
+            subnet_id,
+            SubnetId::compute_subnet::<MainnetEthSpec>(
+                aggregator_slot,
+                com2,
+                committee_count,
+                &subnet_service.beacon_chain.spec,
+            )
+            .unwrap(),
+            "test requires both subscriptions to land on the same subnet"
+        );
+        let subnet = Subnet::Attestation(subnet_id);
+        assert!(
+            !subnet_service.permanent_subscriptions().any(|s| *s == subnet),
+            "test requires a non-permanent subnet"
+        );
+
+        subnet_service
+            .validator_subscriptions(vec![non_aggregator_sub, aggregator_sub].into_iter());
+
+        // The non-aggregator's duty is too close for a discovery window, so the coalesced subnet
+        // is joined immediately and ENR-advertised on behalf of the non-aggregator.
+        let events = get_events(&mut subnet_service, None, 1).await;
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::EnrAdd(s) if *s == subnet)));
+
+        // Once the non-aggregator's slot has passed it expires, but the aggregator-only
+        // subscriber still needs the subnet, so it's demoted off the ENR rather than torn down.
+        let events = get_events(&mut subnet_service, None, 3).await;
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::EnrRemove(s) if *s == subnet)));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::Unsubscribe(s) if *s == subnet)));
+        assert!(subnet_service.is_subscribed(&subnet));
+
+        // Once the aggregator's slot passes too, the subnet is finally torn down.
+        let events = get_events(&mut subnet_service, None, 3).await;
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::Unsubscribe(s) if *s == subnet)));
+    }
+
+    #[tokio::test]
+    async fn idle_mode_tears_down_and_restores_backbone() {
+        let subnets_per_node = MainnetEthSpec::default_spec().subnets_per_node as usize;
+
+        let mut subnet_service = get_subnet_service();
+        assert!(subnet_service.is_gossip_enabled());
+
+        // Drain the initial backbone subscribe/enr/discovery events.
+        let events = get_events(&mut subnet_service, None, 1).await;
+        let subscribe_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::Subscribe(_)))
+            .count();
+        let enr_add_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::EnrAdd(_)))
+            .count();
+        assert_eq!(subscribe_count, subnets_per_node);
+        assert_eq!(enr_add_count, subnets_per_node);
+
+        // 0 validators attached: the backbone should be fully torn down.
+        subnet_service.set_validators_attached(0);
+        assert!(!subnet_service.is_gossip_enabled());
+
+        let events = get_events(&mut subnet_service, None, 2).await;
+        let unsubscribe_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::Unsubscribe(_)))
+            .count();
+        let enr_remove_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::EnrRemove(_)))
+            .count();
+        assert_eq!(unsubscribe_count, subnets_per_node);
+        assert_eq!(enr_remove_count, subnets_per_node);
+        assert_eq!(subnet_service.permanent_subscriptions().count(), 0);
+
+        // No discovery should be emitted while idle, even if peer counts are reported as low.
+        let mut empty_counts = std::collections::HashMap::new();
+        empty_counts.insert(Subnet::Attestation(SubnetId::new(0)), 0);
+        subnet_service.update_subnet_peer_counts(empty_counts);
+        let events = get_events(&mut subnet_service, None, 2).await;
+        assert!(events.is_empty());
+
+        // A validator attaches again: the backbone should be fully re-established.
+        subnet_service.set_validators_attached(1);
+        assert!(subnet_service.is_gossip_enabled());
+
+        let events = get_events(&mut subnet_service, None, 1).await;
+        let subscribe_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::Subscribe(_)))
+            .count();
+        let enr_add_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::EnrAdd(_)))
+            .count();
+        assert_eq!(subscribe_count, subnets_per_node);
+        assert_eq!(enr_add_count, subnets_per_node);
+        assert_eq!(
+            subnet_service.permanent_subscriptions().count(),
+            subnets_per_node
+        );
+    }
+
+    #[tokio::test]
+    async fn subnet_health_check_backs_off_repeated_starvation() {
+        let mut subnet_service = get_subnet_service();
+        // Drain the initial permanent-subnet subscribe/enr/discovery events.
+        let _events = get_events(&mut subnet_service, None, 1).await;
+
+        let subnet = *subnet_service
+            .permanent_subscriptions()
+            .next()
+            .expect("should have at least one permanent subnet");
+
+        let mut starved = std::collections::HashMap::new();
+        starved.insert(subnet, 0);
+
+        // A starved subnet should trigger exactly one re-discovery event.
+        subnet_service.update_subnet_peer_counts(starved.clone());
+        let events = get_events(&mut subnet_service, None, 1).await;
+        let discover_count = events
+            .iter()
+            .filter(|e| matches!(e, SubnetServiceMessage::DiscoverPeers(_)))
+            .count();
+        assert_eq!(discover_count, 1);
+
+        // Reporting the same starvation again immediately should be suppressed by the backoff,
+        // so a persistently starved subnet doesn't spam discovery every slot.
+        subnet_service.update_subnet_peer_counts(starved);
+        let events = get_events(&mut subnet_service, None, 1).await;
+        assert!(events
+            .iter()
+            .all(|e| !matches!(e, SubnetServiceMessage::DiscoverPeers(_))));
+    }
+
+    #[tokio::test]
+    async fn subnet_state_transitions_and_coalesces_overlapping_duties() {
+        let committee_count = 1;
+
+        let mut subnet_service = get_subnet_service();
+        // Drain the initial permanent-subnet subscribe/enr/discovery events.
+        let _events = get_events(&mut subnet_service, None, 1).await;
+
+        let current_slot = subnet_service
+            .beacon_chain
+            .slot_clock
+            .now()
+            .expect("Could not get current slot");
+
+        // Two non-aggregator subscriptions, at different slots, that resolve to the same subnet.
+        let earlier_slot = current_slot + Slot::new(2);
+        let later_slot = current_slot + Slot::new(4);
+        let com1 = 2;
+        let com2 = 0;
+
+        let subnet_id = SubnetId::compute_subnet::<MainnetEthSpec>(
+            earlier_slot,
+            com1,
+            committee_count,
+            &subnet_service.beacon_chain.spec,
+        )
+        .unwrap();
+        assert_eq!(
+            subnet_id,
+            SubnetId::compute_subnet::<MainnetEthSpec>(
+                later_slot,
+                com2,
+                committee_count,
+                &subnet_service.beacon_chain.spec,
+            )
+            .unwrap()
+        );
+        let subnet = Subnet::Attestation(subnet_id);
+
+        assert_eq!(subnet_service.subnet_state(&subnet), None);
+
+        let sub_earlier = get_subscription(com1, earlier_slot, committee_count, false);
+        let sub_later = get_subscription(com2, later_slot, committee_count, false);
+        subnet_service.validator_subscriptions(vec![sub_earlier, sub_later].into_iter());
+
+        if !subnet_service.permanent_subscriptions().any(|s| *s == subnet) {
+            assert_eq!(
+                subnet_service.subnet_state(&subnet),
+                Some(SubnetState::Subscribed)
+            );
+
+            // A single subscribe/discover pair should have been emitted, coalesced across both
+            // overlapping duties rather than generating a second one for the later duty.
+            let events = get_events(&mut subnet_service, None, 1).await;
+            assert_eq!(
+                events
+                    .iter()
+                    .filter(|e| matches!(e, SubnetServiceMessage::Subscribe(s) if *s == subnet))
+                    .count(),
+                1
+            );
+
+            // A slot later, with both duties still pending, the subnet should still be subscribed.
+            let _ = get_events(&mut subnet_service, None, 1).await;
+            assert_eq!(
+                subnet_service.subnet_state(&subnet),
+                Some(SubnetState::Subscribed)
+            );
+
+            // Once the later duty's slot has passed, the coalesced entry should be dropped.
+            let events = get_events(&mut subnet_service, None, 3).await;
+            assert!(events
+                .iter()
+                .any(|e| matches!(e, SubnetServiceMessage::Unsubscribe(s) if *s == subnet)));
+            assert_eq!(subnet_service.subnet_state(&subnet), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_events_fans_out_independently() {
+        let subnets_per_node = MainnetEthSpec::default_spec().subnets_per_node as usize;
+
+        let mut subnet_service = get_subnet_service();
+        let mut receiver_a = subnet_service.subscribe_events();
+        let mut receiver_b = subnet_service.subscribe_events();
+
+        // Both subscribers should independently observe every event the `Stream` yields.
+        let _events = get_events(&mut subnet_service, None, 1).await;
+
+        let mut received_a = Vec::new();
+        while let Ok(event) = receiver_a.try_recv() {
+            received_a.push(event);
+        }
+        let mut received_b = Vec::new();
+        while let Ok(event) = receiver_b.try_recv() {
+            received_b.push(event);
+        }
+        assert_eq!(received_a, received_b);
+        assert_eq!(
+            received_a
+                .iter()
+                .filter(|e| matches!(e, SubnetServiceMessage::Subscribe(_)))
+                .count(),
+            subnets_per_node
+        );
+
+        // Dropping one subscriber must not affect the other, or the primary `Stream` consumer.
+        drop(receiver_a);
+
+        subnet_service.set_validators_attached(0);
+        let _events = get_events(&mut subnet_service, None, 2).await;
+
+        let mut received_b = Vec::new();
+        while let Ok(event) = receiver_b.try_recv() {
+            received_b.push(event);
+        }
+        assert!(received_b
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::Unsubscribe(_))));
+    }
+
+    #[tokio::test]
+    async fn resubscribe_replays_active_subscriptions_without_churn() {
+        let subnets_per_node = MainnetEthSpec::default_spec().subnets_per_node as usize;
+        let committee_count = 1;
+
+        let mut subnet_service = get_subnet_service();
+        // Drain the initial permanent-subnet subscribe/enr/discovery events.
+        let _events = get_events(&mut subnet_service, None, 1).await;
+
+        let current_slot = subnet_service
+            .beacon_chain
+            .slot_clock
+            .now()
+            .expect("Could not get current slot");
+
+        // A short-lived duty subnet, distinct from any permanent subnet.
+        let subscription_slot = current_slot + Slot::new(3);
+        let subnet_id = SubnetId::compute_subnet::<MainnetEthSpec>(
+            subscription_slot,
+            1,
+            committee_count,
+            &subnet_service.beacon_chain.spec,
+        )
+        .unwrap();
+        let subnet = Subnet::Attestation(subnet_id);
+        if !subnet_service.is_subscribed(&subnet) {
+            let sub = get_subscription(1, subscription_slot, committee_count, false);
+            subnet_service.validator_subscriptions(vec![sub].into_iter());
+            let _events = get_events(&mut subnet_service, None, 1).await;
+            assert!(subnet_service.is_subscribed(&subnet));
+
+            // Simulate the network/discv5 layer restarting: the service's own bookkeeping is
+            // unaffected, but the gossipsub mesh and ENR need to be rebuilt from scratch.
+            subnet_service.resubscribe();
+            let events = get_events(&mut subnet_service, None, 1).await;
+
+            let subscribe_count = events
+                .iter()
+                .filter(|e| matches!(e, SubnetServiceMessage::Subscribe(_)))
+                .count();
+            let enr_add_count = events
+                .iter()
+                .filter(|e| matches!(e, SubnetServiceMessage::EnrAdd(_)))
+                .count();
+            // The backbone plus the one non-aggregator duty subnet should be replayed, and
+            // nothing should be spuriously unsubscribed.
+            assert_eq!(subscribe_count, subnets_per_node + 1);
+            assert_eq!(enr_add_count, subnets_per_node + 1);
+            assert!(events
+                .iter()
+                .all(|e| !matches!(e, SubnetServiceMessage::Unsubscribe(_))
+                    && !matches!(e, SubnetServiceMessage::EnrRemove(_))));
+            assert!(subnet_service.is_subscribed(&subnet));
+        }
+    }
+
+    #[tokio::test]
+    async fn slot_gap_retriggers_discovery_in_a_single_fold() {
+        let subnets_per_node = MainnetEthSpec::default_spec().subnets_per_node as usize;
+
+        let mut subnet_service = get_subnet_service();
+        // Drain the initial permanent-subnet subscribe/enr/discovery events and let the service
+        // observe its first real slot.
+        let _events = get_events(&mut subnet_service, None, 1).await;
+
+        // Report every permanent subnet as healthy so the ordinary health check doesn't also
+        // emit a `DiscoverPeers` event on the next tick, which would otherwise be indistinguishable
+        // from the one the slot-gap handling is expected to produce.
+        let healthy_counts: std::collections::HashMap<Subnet, usize> = subnet_service
+            .permanent_subscriptions()
+            .map(|subnet| (*subnet, 100))
+            .collect();
+        subnet_service.update_subnet_peer_counts(healthy_counts);
+        let _events = get_events(&mut subnet_service, None, 0).await;
+
+        // Simulate having fallen behind: pretend the last slot this service actually processed
+        // was several slots ago, so the next tick looks like a multi-slot clock jump.
+        if let Some(current_slot) = subnet_service.current_slot {
+            subnet_service.current_slot = Some(current_slot.saturating_sub(Slot::new(5)));
+        }
+
+        let events = get_events(&mut subnet_service, None, 1).await;
+        let discover_events: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                SubnetServiceMessage::DiscoverPeers(discoveries) => Some(discoveries),
+                _ => None,
+            })
+            .collect();
+
+        // Exactly one fold over the skipped range, covering every currently subscribed subnet,
+        // rather than a separate event per skipped slot.
+        assert_eq!(discover_events.len(), 1);
+        assert_eq!(discover_events[0].len(), subnets_per_node);
+    }
+
+    #[tokio::test]
+    async fn sync_committee_subnet_stays_subscribed_while_any_validator_needs_it() {
+        let current_epoch = Epoch::new(0);
+        let sync_committee_indices = vec![0];
+
+        let mut subnet_service = get_subnet_service();
+        let _events = get_events(&mut subnet_service, None, 0).await;
+
+        let subnet_id = *SyncSubnetId::compute_subnets_for_sync_committee::<MainnetEthSpec>(
+            &sync_committee_indices,
+        )
+        .unwrap()
+        .iter()
+        .next()
+        .unwrap();
+        let subnet = Subnet::SyncCommittee(subnet_id);
+
+        // Two different validators both need the same sync committee subnet, but their
+        // subscriptions end at different epochs.
+        let sub_short_lived = Subscription::SyncCommittee(SyncCommitteeSubscription {
+            validator_index: 0,
+            sync_committee_indices: sync_committee_indices.clone(),
+            until_epoch: current_epoch + 1,
+        });
+        let sub_long_lived = Subscription::SyncCommittee(SyncCommitteeSubscription {
+            validator_index: 1,
+            sync_committee_indices: sync_committee_indices.clone(),
+            until_epoch: current_epoch + 2,
+        });
+        subnet_service.validator_subscriptions(vec![sub_short_lived, sub_long_lived].into_iter());
+        let _events = get_events(&mut subnet_service, None, 1).await;
+        assert!(subnet_service.is_subscribed(&subnet));
+
+        // Once validator 0's subscription alone has elapsed, the subnet must stay subscribed
+        // because validator 1 still depends on it.
+        let events = get_events(
+            &mut subnet_service,
+            None,
+            MainnetEthSpec::slots_per_epoch() as u32,
+        )
+        .await;
+        assert!(events
+            .iter()
+            .all(|e| !matches!(e, SubnetServiceMessage::Unsubscribe(_))));
+        assert!(subnet_service.is_subscribed(&subnet));
+
+        // Once validator 1's subscription also elapses, the subnet is finally dropped.
+        let events = get_events(
+            &mut subnet_service,
+            None,
+            MainnetEthSpec::slots_per_epoch() as u32,
+        )
+        .await;
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SubnetServiceMessage::Unsubscribe(_))));
+        assert!(!subnet_service.is_subscribed(&subnet));
+    }
 }
@@ -51,21 +51,121 @@
 //! ```
 
 use prometheus::{Error, HistogramOpts, Opts};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
-use prometheus::core::{Atomic, GenericGauge, GenericGaugeVec};
+use prometheus::core::{Atomic, Collector, GenericGauge, GenericGaugeVec};
 pub use prometheus::{
     exponential_buckets, linear_buckets,
     proto::{Metric, MetricFamily, MetricType},
     Encoder, Gauge, GaugeVec, Histogram, HistogramTimer, HistogramVec, IntCounter, IntCounterVec,
-    IntGauge, IntGaugeVec, Result, TextEncoder, DEFAULT_BUCKETS,
+    IntGauge, IntGaugeVec, ProtobufEncoder, Result, TextEncoder, DEFAULT_BUCKETS,
 };
 
+mod tcp_exporter;
+pub use tcp_exporter::spawn_tcp_exporter;
+
 /// Collect all the metrics for reporting.
 pub fn gather() -> Vec<prometheus::proto::MetricFamily> {
+    reap_idle_metrics();
     prometheus::gather()
 }
 
+/// A label-vec metric whose individual label-value series can be torn down once idle.
+trait IdleLabelVec: Send + Sync {
+    fn remove(&self, label_values: &[&str]) -> Result<()>;
+}
+
+impl IdleLabelVec for IntGaugeVec {
+    fn remove(&self, label_values: &[&str]) -> Result<()> {
+        self.remove_label_values(label_values)
+    }
+}
+
+impl IdleLabelVec for IntCounterVec {
+    fn remove(&self, label_values: &[&str]) -> Result<()> {
+        self.remove_label_values(label_values)
+    }
+}
+
+impl IdleLabelVec for HistogramVec {
+    fn remove(&self, label_values: &[&str]) -> Result<()> {
+        self.remove_label_values(label_values)
+    }
+}
+
+/// Tracks, per registered label-vec metric, when each label-value series was last touched, so
+/// that idle series can be culled from scrape output. Disabled (`timeout: None`) by default.
+#[derive(Default)]
+struct IdleTracker {
+    timeout: Option<Duration>,
+    vecs: HashMap<String, Box<dyn IdleLabelVec>>,
+    last_touched: HashMap<(String, Vec<String>), Instant>,
+}
+
+static IDLE_TRACKER: LazyLock<Mutex<IdleTracker>> =
+    LazyLock::new(|| Mutex::new(IdleTracker::default()));
+
+/// Enables (or disables, if `None`) idle-metric culling: label-value series of a tracked
+/// `IntGaugeVec`/`IntCounterVec`/`HistogramVec` that haven't been touched for longer than
+/// `timeout` are removed from scrape output the next time `gather()` runs.
+pub fn set_idle_timeout(timeout: Option<Duration>) {
+    let mut tracker = IDLE_TRACKER.lock().unwrap_or_else(|e| e.into_inner());
+    tracker.timeout = timeout;
+    if timeout.is_none() {
+        tracker.last_touched.clear();
+    }
+}
+
+/// Records that `label_values` of the label-vec metric `fq_name` was just touched, resetting its
+/// idle timer. A no-op while idle culling is disabled.
+fn touch_label_values<V: IdleLabelVec + Clone + 'static>(
+    vec: &V,
+    fq_name: &str,
+    label_values: &[&str],
+) {
+    let mut tracker = IDLE_TRACKER.lock().unwrap_or_else(|e| e.into_inner());
+    if tracker.timeout.is_none() {
+        return;
+    }
+    tracker
+        .vecs
+        .entry(fq_name.to_string())
+        .or_insert_with(|| Box::new(vec.clone()));
+    tracker.last_touched.insert(
+        (
+            fq_name.to_string(),
+            label_values.iter().map(|s| s.to_string()).collect(),
+        ),
+        Instant::now(),
+    );
+}
+
+/// Removes every tracked label-value series that has been idle for longer than the configured
+/// timeout. Called automatically from `gather()`; a no-op while idle culling is disabled.
+pub fn reap_idle_metrics() {
+    let mut tracker = IDLE_TRACKER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(timeout) = tracker.timeout else {
+        return;
+    };
+    let now = Instant::now();
+    let idle: Vec<(String, Vec<String>)> = tracker
+        .last_touched
+        .iter()
+        .filter(|(_, touched)| now.duration_since(**touched) > timeout)
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    for (fq_name, label_values) in idle {
+        if let Some(vec) = tracker.vecs.get(&fq_name) {
+            let values: Vec<&str> = label_values.iter().map(String::as_str).collect();
+            let _ = vec.remove(&values);
+        }
+        tracker.last_touched.remove(&(fq_name, label_values));
+    }
+}
+
 /// Attempts to create an `IntCounter`, returning `Err` if the registry does not accept the counter
 /// (potentially due to naming conflict).
 pub fn try_create_int_counter(name: &str, help: &str) -> Result<IntCounter> {
@@ -75,6 +175,131 @@ pub fn try_create_int_counter(name: &str, help: &str) -> Result<IntCounter> {
     Ok(counter)
 }
 
+/// The unit a metric's values are measured in.
+///
+/// Declaring a unit on a metric appends the Prometheus-conventional name suffix (e.g.
+/// `_seconds`, `_bytes`) if it isn't already present, and records the unit so `encode_with_units`
+/// can annotate it with a `# UNIT` comment borrowed from the OpenMetrics metadata convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Seconds,
+    Milliseconds,
+    Bytes,
+    Ratio,
+    Percent,
+    Count,
+}
+
+impl Unit {
+    /// The name suffix conventionally used for metrics of this unit, or `None` if metrics of
+    /// this unit are not suffixed.
+    fn suffix(self) -> Option<&'static str> {
+        match self {
+            Unit::Seconds => Some("seconds"),
+            Unit::Milliseconds => Some("milliseconds"),
+            Unit::Bytes => Some("bytes"),
+            Unit::Ratio => Some("ratio"),
+            Unit::Percent => Some("percent"),
+            Unit::Count => None,
+        }
+    }
+
+    /// The value written on the `# UNIT` annotation.
+    fn as_str(self) -> &'static str {
+        self.suffix().unwrap_or("")
+    }
+}
+
+/// Maps a registered metric name to the `Unit` it was created with, so `encode_with_units` can
+/// annotate it with a `# UNIT` comment without threading the unit through every call site.
+static METRIC_UNITS: LazyLock<Mutex<HashMap<String, Unit>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Appends `unit`'s conventional name suffix to `name`, unless it's already present.
+fn name_with_unit_suffix(name: &str, unit: Unit) -> String {
+    match unit.suffix() {
+        Some(suffix) if !name.ends_with(suffix) => format!("{name}_{suffix}"),
+        _ => name.to_string(),
+    }
+}
+
+fn register_unit(name: &str, unit: Unit) {
+    METRIC_UNITS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_string(), unit);
+}
+
+/// Attempts to create an `IntCounter` with a `Unit`, returning `Err` if the registry does not
+/// accept the counter (potentially due to naming conflict).
+pub fn try_create_int_counter_with_unit(name: &str, help: &str, unit: Unit) -> Result<IntCounter> {
+    let name = name_with_unit_suffix(name, unit);
+    let counter = try_create_int_counter(&name, help)?;
+    register_unit(&name, unit);
+    Ok(counter)
+}
+
+/// Attempts to create an `IntGauge` with a `Unit`, returning `Err` if the registry does not
+/// accept the gauge (potentially due to naming conflict).
+pub fn try_create_int_gauge_with_unit(name: &str, help: &str, unit: Unit) -> Result<IntGauge> {
+    let name = name_with_unit_suffix(name, unit);
+    let gauge = try_create_int_gauge(&name, help)?;
+    register_unit(&name, unit);
+    Ok(gauge)
+}
+
+/// Attempts to create a `Histogram` with specified buckets and a `Unit`, returning `Err` if the
+/// registry does not accept the histogram (potentially due to naming conflict) or no valid buckets
+/// are provided.
+pub fn try_create_histogram_with_unit(
+    name: &str,
+    help: &str,
+    unit: Unit,
+    buckets: Result<Vec<f64>>,
+) -> Result<Histogram> {
+    let name = name_with_unit_suffix(name, unit);
+    let histogram = try_create_histogram_with_buckets(&name, help, buckets)?;
+    register_unit(&name, unit);
+    Ok(histogram)
+}
+
+/// Encodes `families` as standard Prometheus text, then appends a `# UNIT` comment after the
+/// `# TYPE` line of every metric that was created via a `try_create_*_with_unit` constructor with
+/// a unit other than `Unit::Count`; `Unit::Count` metrics get no `# UNIT` line.
+///
+/// This is a best-effort annotation borrowed from the OpenMetrics metadata convention, not a
+/// real OpenMetrics encoding: the output is still Prometheus's text format (Prometheus float and
+/// escaping rules, no `# EOF` terminator, no OpenMetrics counter `_total` naming), and a `# UNIT`
+/// line appearing where OpenMetrics doesn't expect one may confuse a strict OpenMetrics parser.
+/// It's meant to be read by humans and ad hoc tooling tailing `encode_with_units`'s output, not
+/// fed to a parser that enforces the OpenMetrics exposition format.
+pub fn encode_with_units(families: &[MetricFamily]) -> Result<String> {
+    let mut buf = Vec::new();
+    TextEncoder::new().encode(families, &mut buf)?;
+    let text = String::from_utf8(buf)
+        .map_err(|e| Error::Msg(format!("metrics are not valid utf8: {e}")))?;
+
+    let units = METRIC_UNITS.lock().unwrap_or_else(|e| e.into_inner());
+    if units.is_empty() {
+        return Ok(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        out.push_str(line);
+        out.push('\n');
+        if let Some(name) = line
+            .strip_prefix("# TYPE ")
+            .and_then(|rest| rest.split(' ').next())
+        {
+            if let Some(unit) = units.get(name).filter(|unit| unit.suffix().is_some()) {
+                out.push_str(&format!("# UNIT {name} {}\n", unit.as_str()));
+            }
+        }
+    }
+    Ok(out)
+}
+
 /// Attempts to create an `IntGauge`, returning `Err` if the registry does not accept the counter
 /// (potentially due to naming conflict).
 pub fn try_create_int_gauge(name: &str, help: &str) -> Result<IntGauge> {
@@ -99,6 +324,39 @@ pub fn try_create_histogram(name: &str, help: &str) -> Result<Histogram> {
     try_create_histogram_with_buckets(name, help, Ok(DEFAULT_BUCKETS.to_vec()))
 }
 
+/// A wrapper around a `Histogram` which only accepts `Duration` observations, saving call sites
+/// from having to pick their own buckets or remember to convert to seconds.
+///
+/// Create one with `try_create_duration_histogram`.
+#[derive(Clone)]
+pub struct DurationHistogram(Histogram);
+
+impl DurationHistogram {
+    fn new(histogram: Histogram) -> Self {
+        Self(histogram)
+    }
+
+    /// Observes `duration` directly, without the caller needing to convert it to seconds.
+    pub fn observe_duration(&self, duration: Duration) {
+        self.0.observe(duration_to_f64(duration))
+    }
+
+    /// Starts a timer that will be stopped (and the resulting duration observed) when it is
+    /// dropped, or when it is passed as `Some(timer)` to `stop_timer(..)`.
+    pub fn start_timer(&self) -> HistogramTimer {
+        self.0.start_timer()
+    }
+}
+
+/// Attempts to create a `DurationHistogram`, returning `Err` if the registry does not accept the
+/// histogram (potentially due to naming conflict).
+///
+/// The histogram is created with a default set of buckets suited to latency measurements,
+/// ranging from microseconds to tens of seconds.
+pub fn try_create_duration_histogram(name: &str, help: &str) -> Result<DurationHistogram> {
+    try_create_histogram_with_buckets(name, help, decimal_buckets(-6, 1)).map(DurationHistogram::new)
+}
+
 /// Attempts to create a `Histogram` with specified buckets, returning `Err` if the registry does not accept the counter
 /// (potentially due to naming conflict) or no valid buckets are provided.
 pub fn try_create_histogram_with_buckets(
@@ -178,6 +436,9 @@ pub fn try_create_int_counter_vec(
 /// If `int_gauge_vec.is_ok()`, returns a gauge with the given `name`.
 pub fn get_int_gauge(int_gauge_vec: &Result<IntGaugeVec>, name: &[&str]) -> Option<IntGauge> {
     if let Ok(int_gauge_vec) = int_gauge_vec {
+        if let Some(fq_name) = int_gauge_vec.desc().first().map(|d| d.fq_name.clone()) {
+            touch_label_values(int_gauge_vec, &fq_name, name);
+        }
         Some(int_gauge_vec.get_metric_with_label_values(name).ok()?)
     } else {
         None
@@ -208,16 +469,12 @@ pub fn set_gauge_entry<P: Atomic>(
 /// If `int_gauge_vec.is_ok()`, sets the gauge with the given `name` to the given `value`
 /// otherwise returns false.
 pub fn set_int_gauge(int_gauge_vec: &Result<IntGaugeVec>, name: &[&str], value: i64) -> bool {
-    if let Ok(int_gauge_vec) = int_gauge_vec {
-        int_gauge_vec
-            .get_metric_with_label_values(name)
-            .map(|v| {
-                v.set(value);
-                true
-            })
-            .unwrap_or_else(|_| false)
-    } else {
-        false
+    match get_int_gauge(int_gauge_vec, name) {
+        Some(v) => {
+            v.set(value);
+            true
+        }
+        None => false,
     }
 }
 
@@ -227,6 +484,9 @@ pub fn get_int_counter(
     name: &[&str],
 ) -> Option<IntCounter> {
     if let Ok(int_counter_vec) = int_counter_vec {
+        if let Some(fq_name) = int_counter_vec.desc().first().map(|d| d.fq_name.clone()) {
+            touch_label_values(int_counter_vec, &fq_name, name);
+        }
         Some(int_counter_vec.get_metric_with_label_values(name).ok()?)
     } else {
         None
@@ -249,6 +509,9 @@ pub fn inc_counter_vec_by(int_counter_vec: &Result<IntCounterVec>, name: &[&str]
 /// If `histogram_vec.is_ok()`, returns a histogram with the given `name`.
 pub fn get_histogram(histogram_vec: &Result<HistogramVec>, name: &[&str]) -> Option<Histogram> {
     if let Ok(histogram_vec) = histogram_vec {
+        if let Some(fq_name) = histogram_vec.desc().first().map(|d| d.fq_name.clone()) {
+            touch_label_values(histogram_vec, &fq_name, name);
+        }
         Some(histogram_vec.get_metric_with_label_values(name).ok()?)
     } else {
         None
@@ -415,6 +678,190 @@ pub fn decimal_buckets(min_power: i32, max_power: i32) -> Result<Vec<f64>> {
     Ok(buckets)
 }
 
+/// Common behaviour of a thread-local buffered metric handle: publishes everything accumulated
+/// in thread-local storage to the underlying global metric.
+pub trait LocalFlush {
+    fn flush(&self);
+}
+
+/// A thread-local buffered handle onto an `IntCounter`. `inc`/`inc_by` only touch thread-local
+/// storage; call `flush()` (or drop a `flush_on_drop()` guard) to publish the buffered count to
+/// the global counter. Useful in hot loops, such as per-block processing, that would otherwise
+/// contend on the same atomic counter from many tasks.
+pub struct LocalCounter(prometheus::local::LocalIntCounter);
+
+impl LocalCounter {
+    pub fn inc(&self) {
+        self.0.inc()
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.0.inc_by(value)
+    }
+
+    /// Returns an RAII guard that flushes this handle to the global counter when dropped.
+    pub fn flush_on_drop(&self) -> FlushGuard<'_, Self> {
+        FlushGuard(self)
+    }
+}
+
+impl LocalFlush for LocalCounter {
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Returns a thread-local buffered handle onto `counter`, or `None` if `counter` failed to
+/// register.
+pub fn local_counter(counter: &Result<IntCounter>) -> Option<LocalCounter> {
+    counter.as_ref().ok().map(|c| LocalCounter(c.local()))
+}
+
+/// A thread-local buffered handle onto a `Histogram`. `observe`/`observe_duration` only touch
+/// thread-local storage; call `flush()` (or drop a `flush_on_drop()` guard) to publish the
+/// buffered observations to the global histogram.
+pub struct LocalHistogram(prometheus::local::LocalHistogram);
+
+impl LocalHistogram {
+    pub fn observe(&self, value: f64) {
+        self.0.observe(value)
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.0.observe(duration_to_f64(duration))
+    }
+
+    /// Returns an RAII guard that flushes this handle to the global histogram when dropped.
+    pub fn flush_on_drop(&self) -> FlushGuard<'_, Self> {
+        FlushGuard(self)
+    }
+}
+
+impl LocalFlush for LocalHistogram {
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Returns a thread-local buffered handle onto `histogram`, or `None` if `histogram` failed to
+/// register.
+pub fn local_histogram(histogram: &Result<Histogram>) -> Option<LocalHistogram> {
+    histogram.as_ref().ok().map(|h| LocalHistogram(h.local()))
+}
+
+/// Flushes a thread-local buffered metric handle when dropped, so a hot loop can accumulate
+/// counts/observations locally and flush once (e.g. per block or epoch) without having to
+/// remember to call `flush()` on every exit path.
+pub struct FlushGuard<'a, T: LocalFlush>(&'a T);
+
+impl<T: LocalFlush> Drop for FlushGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.flush();
+    }
+}
+
+/// A namespaced group of metrics, registered into the global registry as a single `Collector`.
+///
+/// The registry is one flat global namespace, so every metric `name` must be unique; collisions
+/// only surface as a runtime `Err` from the `try_create_*` helper that loses the race. A
+/// `MetricGroup` makes that far harder to get wrong: every metric it creates is auto-prefixed
+/// with the group's module name, and the whole group registers (or, by simply not calling
+/// `register`, doesn't) as a single atomic unit rather than one `prometheus::register` call per
+/// metric.
+///
+/// ```rust,ignore
+/// let mut group = MetricGroup::new("beacon_chain");
+/// let blocks_processed = group.int_counter("blocks_processed_total", "Number of blocks processed")?;
+/// group.register()?;
+/// ```
+pub struct MetricGroup {
+    prefix: String,
+    collectors: Vec<Box<dyn prometheus::core::Collector>>,
+}
+
+impl MetricGroup {
+    /// Creates a group whose metrics are named `{prefix}_{name}`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            collectors: Vec::new(),
+        }
+    }
+
+    fn prefixed(&self, name: &str) -> String {
+        format!("{}_{}", self.prefix, name)
+    }
+
+    pub fn int_counter(&mut self, name: &str, help: &str) -> Result<IntCounter> {
+        let counter = IntCounter::with_opts(Opts::new(self.prefixed(name), help))?;
+        self.collectors.push(Box::new(counter.clone()));
+        Ok(counter)
+    }
+
+    pub fn int_gauge(&mut self, name: &str, help: &str) -> Result<IntGauge> {
+        let gauge = IntGauge::with_opts(Opts::new(self.prefixed(name), help))?;
+        self.collectors.push(Box::new(gauge.clone()));
+        Ok(gauge)
+    }
+
+    pub fn histogram(&mut self, name: &str, help: &str) -> Result<Histogram> {
+        let opts = HistogramOpts::new(self.prefixed(name), help).buckets(DEFAULT_BUCKETS.to_vec());
+        let histogram = Histogram::with_opts(opts)?;
+        self.collectors.push(Box::new(histogram.clone()));
+        Ok(histogram)
+    }
+
+    pub fn histogram_vec(
+        &mut self,
+        name: &str,
+        help: &str,
+        label_names: &[&str],
+    ) -> Result<HistogramVec> {
+        let opts = HistogramOpts::new(self.prefixed(name), help).buckets(DEFAULT_BUCKETS.to_vec());
+        let histogram_vec = HistogramVec::new(opts, label_names)?;
+        self.collectors.push(Box::new(histogram_vec.clone()));
+        Ok(histogram_vec)
+    }
+
+    pub fn int_gauge_vec(
+        &mut self,
+        name: &str,
+        help: &str,
+        label_names: &[&str],
+    ) -> Result<IntGaugeVec> {
+        let gauge_vec = IntGaugeVec::new(Opts::new(self.prefixed(name), help), label_names)?;
+        self.collectors.push(Box::new(gauge_vec.clone()));
+        Ok(gauge_vec)
+    }
+
+    pub fn int_counter_vec(
+        &mut self,
+        name: &str,
+        help: &str,
+        label_names: &[&str],
+    ) -> Result<IntCounterVec> {
+        let counter_vec = IntCounterVec::new(Opts::new(self.prefixed(name), help), label_names)?;
+        self.collectors.push(Box::new(counter_vec.clone()));
+        Ok(counter_vec)
+    }
+
+    /// Registers every metric created from this group with the global registry in one step,
+    /// consuming the builder.
+    pub fn register(self) -> Result<()> {
+        prometheus::register(Box::new(self))
+    }
+}
+
+impl prometheus::core::Collector for MetricGroup {
+    fn desc(&self) -> Vec<&prometheus::core::Desc> {
+        self.collectors.iter().flat_map(|c| c.desc()).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.collectors.iter().flat_map(|c| c.collect()).collect()
+    }
+}
+
 /// Would be nice to use the `Try` trait bound and have a single implementation, but try_trait_v2
 /// is not a stable feature yet.
 pub trait TryExt {
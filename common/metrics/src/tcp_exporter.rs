@@ -0,0 +1,121 @@
+//! An optional push-based exporter, alongside the pull-based `gather()` + `TextEncoder` path,
+//! for tailing metrics live without standing up a Prometheus scrape pipeline.
+//!
+//! Modelled on `metrics-exporter-tcp`: a background task snapshots `gather()` on an interval and
+//! streams the encoded `MetricFamily` protobufs, each prefixed with a 4-byte big-endian length,
+//! to every connected client. Slow or disconnected clients are dropped rather than allowed to
+//! block the snapshot loop or other observers.
+
+use crate::gather;
+use prometheus::{Encoder, ProtobufEncoder};
+use slog::{error, o, warn, Logger};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+type ClientId = u64;
+
+/// Spawns a background task that accepts TCP connections on `addr` and, every `interval`,
+/// streams a length-prefixed, protobuf-encoded snapshot of `gather()` to each connected client.
+///
+/// Must be called from within a running Tokio runtime.
+pub fn spawn_tcp_exporter(addr: SocketAddr, interval: Duration, log: &Logger) {
+    let log = log.new(o!("service" => "tcp_exporter"));
+    let clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<Vec<u8>>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+
+    tokio::spawn(accept_loop(addr, clients.clone(), log));
+    tokio::spawn(snapshot_loop(interval, clients));
+}
+
+/// Accepts incoming connections and registers each one's write half as a client, buffering
+/// outgoing frames on a bounded channel so a slow reader can't stall the snapshot loop.
+///
+/// A bind failure is fatal to the exporter and is logged as an error, since every client relying
+/// on it for live metric tailing will silently get nothing. A failed `accept()` doesn't bring the
+/// listener down, but is logged as a warning since a run of them can mean the listener itself has
+/// gone bad.
+async fn accept_loop(
+    addr: SocketAddr,
+    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<Vec<u8>>>>>,
+    log: Logger,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(log, "Failed to bind tcp exporter"; "addr" => %addr, "error" => %e);
+            return;
+        }
+    };
+
+    let mut next_id: ClientId = 0;
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(log, "Failed to accept tcp exporter connection"; "error" => %e);
+                continue;
+            }
+        };
+
+        let id = next_id;
+        next_id += 1;
+        let (tx, rx) = mpsc::channel::<Vec<u8>>(8);
+        clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, tx);
+
+        tokio::spawn(write_loop(id, stream, rx, clients.clone()));
+    }
+}
+
+/// Forwards buffered frames to a single client, dropping its registration once it disconnects.
+async fn write_loop(
+    id: ClientId,
+    mut stream: tokio::net::TcpStream,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<Vec<u8>>>>>,
+) {
+    while let Some(frame) = rx.recv().await {
+        if stream.write_all(&frame).await.is_err() {
+            break;
+        }
+    }
+    clients.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+}
+
+/// Periodically encodes `gather()` as length-prefixed protobuf frames and pushes each snapshot's
+/// frames to every connected client as a single buffered message, dropping any client whose
+/// buffer is full or whose receiver has gone away.
+async fn snapshot_loop(
+    interval: Duration,
+    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<Vec<u8>>>>>,
+) {
+    let encoder = ProtobufEncoder::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        let families = gather();
+
+        let mut snapshot = Vec::new();
+        for family in &families {
+            let mut buf = Vec::new();
+            if encoder.encode(std::slice::from_ref(family), &mut buf).is_err() {
+                continue;
+            }
+            snapshot.extend_from_slice(&(buf.len() as u32).to_be_bytes());
+            snapshot.extend_from_slice(&buf);
+        }
+
+        clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, tx| tx.try_send(snapshot.clone()).is_ok());
+    }
+}